@@ -1,5 +1,11 @@
+use std::{future::{Future, IntoFuture}, time::Duration};
+
 use axum::Router;
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, signal, sync::oneshot, time::timeout};
+use tracing::{info, warn};
+
+// 停止シグナルを受け取ってから処理中のリクエストを待つ最大時間
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub async fn startup() {
     // リクエストサイズを制限する
@@ -17,7 +23,65 @@ pub async fn startup() {
     
     let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
     // let listener = TcpListener::bind("0.0.0.0:443").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    serve(listener, app, shutdown_signal(), DRAIN_TIMEOUT).await;
+}
+
+async fn serve<F>(listener: TcpListener, app: Router, signal: F, drain_timeout: Duration)
+where
+    F: Future<Output = ()> + Send + 'static
+{
+    // シグナルを受け取った時点からドレインの制限時間を計測する
+    let (signaled_tx, signaled_rx) = oneshot::channel();
+
+    let mut server = axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            signal.await;
+            let _ = signaled_tx.send(());
+        })
+        .into_future();
+
+    tokio::select! {
+        result = &mut server => result.unwrap(),
+        _ = signaled_rx => match timeout(drain_timeout, &mut server).await {
+            Ok(result) => result.unwrap(),
+            Err(_) => {
+                // 長時間のリクエストで停止が妨げられないよう、待たずに終了する
+                warn!(
+                    drain_timeout_secs = drain_timeout.as_secs_f64(),
+                    "処理中のリクエストが制限時間内に完了しなかったため、待たずにサーバーを停止します"
+                );
+                return;
+            }
+        }
+    }
+
+    // 処理中のリクエストを全て捌き切ってから出力する
+    info!("サーバーを停止しました");
+}
+
+// SIGINT(Ctrl+C)またはSIGTERMを受け取るまで待機する
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("SIGINTハンドラの登録に失敗しました");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("SIGTERMハンドラの登録に失敗しました")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
 
 /*
@@ -45,4 +109,102 @@ pub async fn startup(modules: Arc<Modules>) {
 pub fn init_app() {
     dotenv().ok();
     tracing_subscriber::fmt::init();
-} */
\ No newline at end of file
+} */
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+
+    use axum::{routing::get, Router};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, sync::{oneshot, Notify}, task::JoinHandle, time::{sleep, timeout}};
+
+    use super::serve;
+
+    const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[derive(Default)]
+    struct Progress {
+        started: Notify,
+        finished: AtomicBool,
+    }
+
+    // 呼び出されたことを通知してから`duration`だけ待機するハンドラ
+    fn slow_app(duration: Duration, progress: Arc<Progress>) -> Router {
+        Router::new().route("/", get(move || {
+            let progress = progress.clone();
+            async move {
+                progress.started.notify_one();
+                sleep(duration).await;
+                progress.finished.store(true, Ordering::SeqCst);
+                "done"
+            }
+        }))
+    }
+
+    async fn spawn_server(app: Router) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let server = tokio::spawn(serve(listener, app, async {
+            let _ = rx.await;
+        }, DRAIN_TIMEOUT));
+
+        (addr, tx, server)
+    }
+
+    async fn request(addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn stop_on_shutdown_signal() {
+        let (_, tx, server) = spawn_server(Router::new()).await;
+
+        tx.send(()).unwrap();
+
+        let result = timeout(TEST_TIMEOUT, server).await;
+        assert!(matches!(result, Ok(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn drain_in_flight_request() {
+        let progress = Arc::new(Progress::default());
+        let (addr, tx, server) = spawn_server(slow_app(Duration::from_millis(50), progress.clone())).await;
+
+        let client = tokio::spawn(request(addr));
+        progress.started.notified().await;
+        tx.send(()).unwrap();
+
+        let result = timeout(TEST_TIMEOUT, server).await;
+        assert!(matches!(result, Ok(Ok(()))));
+
+        // 接続は`serve`とは別のタスクで処理されるため、レスポンスの受信だけでなく
+        // `serve`が戻る前にハンドラが完了していたことも確認する
+        assert!(progress.finished.load(Ordering::SeqCst));
+
+        let response = timeout(TEST_TIMEOUT, client).await.unwrap().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("done"));
+    }
+
+    #[tokio::test]
+    async fn stop_after_drain_timeout() {
+        let progress = Arc::new(Progress::default());
+        let (addr, tx, server) = spawn_server(slow_app(Duration::from_secs(60), progress.clone())).await;
+
+        let _client = tokio::spawn(request(addr));
+        progress.started.notified().await;
+        tx.send(()).unwrap();
+
+        let result = timeout(TEST_TIMEOUT, server).await;
+        assert!(matches!(result, Ok(Ok(()))));
+        assert!(!progress.finished.load(Ordering::SeqCst));
+    }
+}